@@ -0,0 +1,63 @@
+use ethereum_tx_sign::web3::types::{Address, H256, U256};
+
+use sha3::{Digest, Keccak256};
+
+/// Canonical signature of the ERC20 `Transfer` event. Its keccak256 hash is
+/// `topic0` of every `Transfer` log and is what we filter the node's logs on.
+pub const TRANSFER_SIGNATURE: &str = "Transfer(address,address,uint256)";
+
+/// `keccak256("Transfer(address,address,uint256)")` — the `topic0` that marks a
+/// `Transfer` log for any ERC20 contract.
+pub fn transfer_topic() -> H256 {
+    H256::from_slice(&Keccak256::digest(TRANSFER_SIGNATURE.as_bytes()))
+}
+
+/// Left-pad a 20-byte address to a 32-byte topic, matching the way `indexed`
+/// address parameters are encoded in event topics. Used to filter on `topic2`
+/// (the transfer recipient) so we only see transfers into our own address.
+pub fn address_to_topic(address: Address) -> H256 {
+    let mut topic = [0u8; 32];
+    topic[12..].copy_from_slice(address.as_bytes());
+    H256::from(topic)
+}
+
+/// Decode the settled amount from a `Transfer` log's data field. The value is a
+/// single big-endian 32-byte word; anything shorter is a malformed log.
+pub fn decode_transfer_amount(data: &[u8]) -> Option<U256> {
+    if data.len() < 32 {
+        return None;
+    }
+    Some(U256::from_big_endian(&data[..32]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn transfer_topic_matches_known_hash() {
+        // The well-known ERC20 Transfer topic0.
+        let expected = H256::from_str(
+            "ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef",
+        )
+        .unwrap();
+        assert_eq!(transfer_topic(), expected);
+    }
+
+    #[test]
+    fn address_is_right_aligned_in_topic() {
+        let address = Address::from_str("3cdb3d9e1b74692bb1e3bb5fc81938151ca64b02").unwrap();
+        let topic = address_to_topic(address);
+        assert_eq!(&topic.as_bytes()[..12], &[0u8; 12]);
+        assert_eq!(&topic.as_bytes()[12..], address.as_bytes());
+    }
+
+    #[test]
+    fn decodes_word_sized_amount() {
+        let mut data = [0u8; 32];
+        data[31] = 0x2a;
+        assert_eq!(decode_transfer_amount(&data), Some(U256::from(42)));
+        assert_eq!(decode_transfer_amount(&data[..16]), None);
+    }
+}
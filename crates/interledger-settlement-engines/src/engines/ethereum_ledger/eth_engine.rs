@@ -0,0 +1,438 @@
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use ethereum_tx_sign::web3::{
+    api::Web3,
+    futures::{
+        future::{err, join_all, ok, Either, Future},
+        stream, Stream,
+    },
+    transports::Http,
+    types::{Address, BlockId, BlockNumber, FilterBuilder, U256},
+};
+
+use bytes::Bytes;
+use hyper::StatusCode;
+use interledger_service::{Account, AccountStore};
+use interledger_settlement::IdempotentStore;
+use reqwest::r#async::Client;
+use serde_json::json;
+use url::Url;
+
+use super::erc20::{address_to_topic, decode_transfer_amount, transfer_topic};
+use super::settlement_auth::{verify_settlement, Signature};
+use super::types::{
+    Addresses, EthereumAccount, EthereumLedgerTxSigner, EthereumStore, ObservedBlock,
+};
+
+/// Settlement engine that watches an Ethereum node for incoming transfers and
+/// notifies the connector so peers can be credited.
+#[derive(Clone)]
+pub struct EthereumLedgerSettlementEngine<S, Si, A> {
+    web3: Web3<Http>,
+    store: S,
+    signer: Si,
+    chain_id: u8,
+    /// Number of confirmations a block needs before a transfer in it is safe to
+    /// credit. Also bounds how far a reorg can ever roll the cursor back.
+    confs: usize,
+    poll_frequency: Duration,
+    connector_url: Url,
+    http_client: Client,
+    token_address: Option<Address>,
+    watch_incoming: bool,
+    account_type: PhantomData<A>,
+}
+
+impl<S, Si, A> EthereumLedgerSettlementEngine<S, Si, A>
+where
+    S: EthereumStore<Account = A> + AccountStore<Account = A> + Clone + Send + Sync + 'static,
+    Si: EthereumLedgerTxSigner + Clone + Send + Sync + 'static,
+    A: EthereumAccount + Send + Sync + 'static,
+    <A as Account>::AccountId: serde::Serialize + serde::de::DeserializeOwned + Into<u64> + Copy,
+{
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        endpoint: String,
+        store: S,
+        signer: Si,
+        chain_id: u8,
+        confs: usize,
+        poll_frequency: Duration,
+        connector_url: Url,
+        token_address: Option<Address>,
+        watch_incoming: bool,
+    ) -> Self {
+        let (eloop, transport) =
+            Http::new(&endpoint).expect("failed to connect to ethereum endpoint");
+        // The transport's event loop must keep running for the life of the
+        // engine; detach it from this scope so it is not dropped.
+        eloop.into_remote();
+        EthereumLedgerSettlementEngine {
+            web3: Web3::new(transport),
+            store,
+            signer,
+            chain_id,
+            confs,
+            poll_frequency,
+            connector_url,
+            http_client: Client::new(),
+            token_address,
+            watch_incoming,
+            account_type: PhantomData,
+        }
+    }
+
+    /// The settlement address this engine watches and signs from.
+    fn own_address(&self) -> Address {
+        self.signer.address()
+    }
+
+    /// POST a settlement notification to the connector so it credits `account_id`
+    /// with `amount`. A signature + nonce over the tuple is attached so the
+    /// connector can verify the notification really came from us.
+    fn notify_connector(
+        &self,
+        account_id: u64,
+        amount: U256,
+    ) -> impl Future<Item = (), Error = ()> {
+        let nonce = U256::from(account_id).saturating_add(amount);
+        let signature = self.signer.sign_settlement(account_id, amount, nonce);
+        let url = format!(
+            "{}accounts/{}/settlements",
+            self.connector_url.as_str(),
+            account_id
+        );
+        let body = json!({
+            "amount": amount.to_string(),
+            "nonce": nonce.to_string(),
+            "signature": hex::encode(&signature.to_bytes()[..]),
+        });
+        self.http_client
+            .post(&url)
+            .json(&body)
+            .send()
+            .map(|_| ())
+            .map_err(|_| ())
+    }
+
+    /// Reconcile our observed-block window with the node's current chain,
+    /// rolling the cursor back if a reorg is detected, then re-scan forward and
+    /// credit any transfers that are now buried at least `confs` deep.
+    pub fn sync_incoming(&self) -> impl Future<Item = (), Error = ()> {
+        if !self.watch_incoming {
+            return Either::A(ok(()));
+        }
+
+        let engine = self.clone();
+        let confs = U256::from(self.confs);
+        Either::B(
+            self.web3
+                .eth()
+                .block_number()
+                .map_err(|_| ())
+                .join(self.store.load_recently_observed_data())
+                .and_then(move |(tip, window)| {
+                    // Highest block we are allowed to credit: everything at or
+                    // below this is at least `confs` deep.
+                    let safe_tip = tip.saturating_sub(confs);
+                    engine
+                        .find_reorg_point(&window)
+                        .and_then(move |reorg_point| {
+                            // Roll back to just before the reorg, but never past
+                            // the confirmation horizon.
+                            let resume_from = match reorg_point {
+                                Some(point) => std::cmp::min(point, safe_tip),
+                                None => match window.last() {
+                                    // No reorg: resume after the last observed block.
+                                    Some((n, _, _)) => n.saturating_add(U256::one()),
+                                    // First run: start at the current safe tip
+                                    // rather than replaying the chain from genesis.
+                                    None => safe_tip,
+                                },
+                            };
+                            // Scan native ETH transfers and, when configured, the
+                            // engine's ERC20 token contract over the same range.
+                            let token_scan = match engine.token_address {
+                                Some(token) => Either::A(engine.scan_token_transfers(
+                                    token,
+                                    engine.own_address(),
+                                    resume_from,
+                                    safe_tip,
+                                )),
+                                None => Either::B(ok(())),
+                            };
+                            engine
+                                .scan_and_credit(resume_from, safe_tip)
+                                .join(token_scan)
+                                .map(|_| ())
+                        })
+                }),
+        )
+    }
+
+    /// Compare every stored hash in the observed window against the hash the
+    /// node currently reports for that height. The reorg point is the oldest
+    /// mismatching height — the earliest height whose contents changed and
+    /// therefore must be re-scanned. `None` means the window still matches the
+    /// canonical chain.
+    fn find_reorg_point(
+        &self,
+        window: &[ObservedBlock],
+    ) -> impl Future<Item = Option<U256>, Error = ()> {
+        let web3 = self.web3.clone();
+        let checks = window
+            .iter()
+            .rev()
+            .map(|(number, stored_hash, _)| {
+                let number = *number;
+                let stored_hash = *stored_hash;
+                web3.eth()
+                    .block(BlockId::Number(BlockNumber::Number(number.as_u64())))
+                    .map_err(|_| ())
+                    .map(move |block| {
+                        let matches = block
+                            .and_then(|b| b.hash)
+                            .map(|hash| hash == stored_hash)
+                            .unwrap_or(false);
+                        (number, matches)
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        join_all(checks).map(|results| {
+            // Results are newest-first; the reorg point is the oldest height
+            // that no longer matches.
+            results
+                .into_iter()
+                .filter(|(_, matches)| !matches)
+                .map(|(number, _)| number)
+                .min()
+        })
+    }
+
+    /// Re-scan the `[from, to]` inclusive range, crediting settlements and
+    /// recording each block in the observed window so future polls can detect
+    /// reorgs against it.
+    fn scan_and_credit(
+        &self,
+        from: U256,
+        to: U256,
+    ) -> impl Future<Item = (), Error = ()> {
+        if from > to {
+            return Either::A(ok(()));
+        }
+
+        let engine = self.clone();
+        let mut heights: Vec<U256> = Vec::new();
+        let mut n = from;
+        while n <= to {
+            heights.push(n);
+            n = n.saturating_add(U256::one());
+        }
+
+        // Credit blocks strictly in order so the observed window is written
+        // monotonically and stays consistent if a poll is interrupted.
+        Either::B(
+            stream::iter_ok::<_, ()>(heights).for_each(move |number| engine.credit_block(number)),
+        )
+    }
+
+    /// Credit native-ETH settlements landing in a single, sufficiently-confirmed
+    /// block and persist it to the observed window. Every transaction paying our
+    /// own address is matched back to the sending peer and forwarded to the
+    /// connector; the balance at this height is recorded alongside the hash so
+    /// the reorg window stays meaningful.
+    fn credit_block(&self, number: U256) -> impl Future<Item = (), Error = ()> {
+        let engine = self.clone();
+        let store = self.store.clone();
+        let own_address = self.own_address();
+        let block_id = BlockId::Number(BlockNumber::Number(number.as_u64()));
+
+        self.web3
+            .eth()
+            .block_with_txs(block_id)
+            .map_err(|_| ())
+            .join(
+                self.web3
+                    .eth()
+                    .balance(own_address, Some(BlockNumber::Number(number.as_u64())))
+                    .map_err(|_| ()),
+            )
+            .and_then(move |(block, balance)| {
+                let block = match block {
+                    Some(block) => block,
+                    None => return Either::A(ok(())),
+                };
+                let hash = block.hash.unwrap_or_default();
+
+                // Credit every transfer into our address, resolving the payer
+                // from the transaction sender.
+                let credits = block.transactions.into_iter().filter_map(move |tx| {
+                    if tx.to == Some(own_address) && !tx.value.is_zero() {
+                        tx.from.map(|from| (from, tx.value))
+                    } else {
+                        None
+                    }
+                });
+
+                let credit = stream::iter_ok::<_, ()>(credits).for_each(move |(from, value)| {
+                    engine.credit_native_transfer(from, value)
+                });
+
+                Either::B(credit.and_then(move |_| {
+                    store.save_recently_observed_data(number, hash, balance)
+                }))
+            })
+    }
+
+    /// Resolve the peer behind a native transfer and notify the connector. An
+    /// unknown sender is ignored rather than credited.
+    fn credit_native_transfer(
+        &self,
+        from: Address,
+        amount: U256,
+    ) -> impl Future<Item = (), Error = ()> {
+        let engine = self.clone();
+        self.store
+            .load_account_id_from_address(Addresses {
+                own_address: from,
+                token_address: None,
+            })
+            .then(move |resolved| match resolved {
+                Ok(account_id) => {
+                    Either::A(engine.notify_connector(account_id.into(), amount))
+                }
+                Err(()) => Either::B(ok(())),
+            })
+    }
+
+    /// Scan a `(own_address, token_address)` pair's `Transfer` logs over the
+    /// `[from, to]` range and credit each inbound transfer to the peer that
+    /// sent it. We filter on `topic0 = keccak(Transfer(address,address,uint256))`
+    /// and `topic2 = our own_address` so the node only returns transfers into
+    /// us, then decode the 32-byte value and resolve the payer from `topic1`.
+    pub fn scan_token_transfers(
+        &self,
+        token_address: Address,
+        own_address: Address,
+        from: U256,
+        to: U256,
+    ) -> impl Future<Item = (), Error = ()> {
+        let engine = self.clone();
+        let store = self.store.clone();
+        let filter = FilterBuilder::default()
+            .address(vec![token_address])
+            .from_block(BlockNumber::Number(from.as_u64()))
+            .to_block(BlockNumber::Number(to.as_u64()))
+            .topics(
+                Some(vec![transfer_topic()]),
+                None,
+                Some(vec![address_to_topic(own_address)]),
+                None,
+            )
+            .build();
+
+        self.web3
+            .eth()
+            .logs(filter)
+            .map_err(|_| ())
+            .and_then(move |logs| {
+                let credits = logs.into_iter().filter_map(move |log| {
+                    // topic1 is the (indexed) sender, right-aligned in 32 bytes.
+                    let from_address = log
+                        .topics
+                        .get(1)
+                        .map(|t| Address::from_slice(&t.as_bytes()[12..]))?;
+                    let amount = decode_transfer_amount(&log.data.0)?;
+                    Some((from_address, amount))
+                });
+
+                stream::iter_ok::<_, ()>(credits).for_each(move |(from_address, amount)| {
+                    // Resolve the paying peer and credit it; a transfer from an
+                    // unknown sender is ignored rather than credited.
+                    let engine = engine.clone();
+                    store
+                        .load_account_id_from_token_transfer(token_address, from_address)
+                        .then(move |resolved| match resolved {
+                            Ok(account_id) => {
+                                Either::A(engine.notify_connector(account_id.into(), amount))
+                            }
+                            Err(()) => Either::B(ok(())),
+                        })
+                })
+            })
+    }
+}
+
+impl<S, Si, A> EthereumLedgerSettlementEngine<S, Si, A>
+where
+    S: EthereumStore<Account = A>
+        + AccountStore<Account = A>
+        + IdempotentStore
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    Si: EthereumLedgerTxSigner + Clone + Send + Sync + 'static,
+    A: EthereumAccount + Send + Sync + 'static,
+    <A as Account>::AccountId: From<u64> + Copy + serde::Serialize,
+{
+    /// Sign an outgoing settlement notification so the receiving connector can
+    /// verify it came from us. The returned signature is attached to the
+    /// message alongside `nonce`.
+    pub fn sign_outgoing_settlement(
+        &self,
+        account_id: u64,
+        amount: U256,
+        nonce: U256,
+    ) -> Signature {
+        self.signer.sign_settlement(account_id, amount, nonce)
+    }
+
+    /// Verify an incoming settlement notification before crediting it. Rejects
+    /// replayed nonces (tracked in the idempotency cache) and signatures whose
+    /// recovered address does not match the account's stored `own_address`. On
+    /// success the nonce is recorded as consumed so it cannot be reused.
+    pub fn verify_incoming_settlement(
+        &self,
+        account_id: u64,
+        amount: U256,
+        nonce: U256,
+        signature: Signature,
+    ) -> impl Future<Item = (), Error = ()> {
+        let store = self.store.clone();
+        let nonce_key = format!("eth:settlement_nonce:{}:{}", account_id, nonce);
+        store
+            .load_idempotent_data(nonce_key.clone())
+            .and_then(move |seen| {
+                if seen.is_some() {
+                    // This nonce was already consumed: a replay, reject it.
+                    return Either::A(err(()));
+                }
+                let store = store.clone();
+                Either::B(
+                    store
+                        .load_account_address(A::AccountId::from(account_id))
+                        .and_then(move |own_address| {
+                            if verify_settlement(account_id, amount, nonce, &signature, own_address)
+                            {
+                                Either::A(
+                                    store
+                                        .save_idempotent_data(
+                                            nonce_key,
+                                            [0u8; 32],
+                                            StatusCode::OK,
+                                            Bytes::new(),
+                                        )
+                                        .map(|_| ()),
+                                )
+                            } else {
+                                // Recovered address is not this account's: forged.
+                                Either::B(err(()))
+                            }
+                        }),
+                )
+            })
+    }
+}
@@ -0,0 +1,121 @@
+use bip39::{Language, Mnemonic};
+use tiny_hderive::bip32::ExtendedPrivKey;
+
+use sha3::{Digest, Keccak256};
+
+use ethereum_tx_sign::web3::types::{Address, U256};
+use ethereum_tx_sign::RawTransaction;
+
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+use super::settlement_auth::{sign_settlement, Signature};
+use super::types::EthereumLedgerTxSigner;
+
+/// Default BIP44 derivation path prefix for Ethereum accounts.
+/// `from_mnemonic_index` appends `/{index}` to it so it lands on the same
+/// account that ganache and Metamask expose by default.
+const DEFAULT_PATH_PREFIX: &str = "m/44'/60'/0'/0";
+
+/// A [`EthereumLedgerTxSigner`] backed by a key derived from a BIP39 mnemonic
+/// and a BIP32 derivation path. This lets an operator point the settlement
+/// engine at the very account ganache was booted with, rather than copying a
+/// raw private key around.
+#[derive(Clone)]
+pub struct HdWalletSigner {
+    secret_key: SecretKey,
+    address: Address,
+}
+
+impl HdWalletSigner {
+    /// Derive a signer from a mnemonic phrase and a full BIP32 derivation path
+    /// (e.g. `m/44'/60'/0'/0/0`). The mnemonic checksum is validated before any
+    /// key material is derived.
+    pub fn from_mnemonic(phrase: &str, path: &str) -> Result<Self, ()> {
+        // `Mnemonic::from_phrase` verifies the BIP39 checksum for us.
+        let mnemonic = Mnemonic::from_phrase(phrase, Language::English).map_err(|_| ())?;
+        let seed = bip39::Seed::new(&mnemonic, "");
+
+        let ext = ExtendedPrivKey::derive(seed.as_bytes(), path).map_err(|_| ())?;
+        let secret_key = SecretKey::from_slice(&ext.secret()).map_err(|_| ())?;
+        let address = public_to_address(&secret_key);
+
+        Ok(HdWalletSigner {
+            secret_key,
+            address,
+        })
+    }
+
+    /// Derive the `index`th account under the default Ethereum path
+    /// (`m/44'/60'/0'/0/{index}`).
+    pub fn from_mnemonic_index(phrase: &str, index: u32) -> Result<Self, ()> {
+        let path = format!("{}/{}", DEFAULT_PATH_PREFIX, index);
+        Self::from_mnemonic(phrase, &path)
+    }
+}
+
+/// Compute the Ethereum address for a secret key: keccak256 of the 64-byte
+/// uncompressed public key (with the `0x04` prefix stripped), last 20 bytes.
+fn public_to_address(secret_key: &SecretKey) -> Address {
+    let secp = Secp256k1::signing_only();
+    let public_key = PublicKey::from_secret_key(&secp, secret_key);
+    let serialized = public_key.serialize_uncompressed();
+    let hash = Keccak256::digest(&serialized[1..]);
+    Address::from_slice(&hash[12..])
+}
+
+impl EthereumLedgerTxSigner for HdWalletSigner {
+    fn sign(&self, tx: RawTransaction, chain_id: &u8) -> Vec<u8> {
+        tx.sign(&self.secret_key, chain_id)
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn sign_settlement(&self, account_id: u64, amount: U256, nonce: U256) -> Signature {
+        sign_settlement(&self.secret_key, account_id, amount, nonce)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    // The mnemonic `start_ganache` boots ganache with.
+    const GANACHE_MNEMONIC: &str =
+        "abstract vacuum mammal awkward pudding scene penalty purchase dinner depart evoke puzzle";
+
+    #[test]
+    fn derives_ganache_account_zero() {
+        let signer = HdWalletSigner::from_mnemonic_index(GANACHE_MNEMONIC, 0).unwrap();
+        // Account 0 as reported by ganache for the mnemonic above.
+        let expected =
+            Address::from_str("3cdb3d9e1b74692bb1e3bb5fc81938151ca64b02").unwrap();
+        assert_eq!(signer.address(), expected);
+    }
+
+    #[test]
+    fn explicit_path_matches_index_helper() {
+        let by_path =
+            HdWalletSigner::from_mnemonic(GANACHE_MNEMONIC, "m/44'/60'/0'/0/1").unwrap();
+        let by_index = HdWalletSigner::from_mnemonic_index(GANACHE_MNEMONIC, 1).unwrap();
+        assert_eq!(by_path.address(), by_index.address());
+    }
+
+    #[test]
+    fn settlement_signature_recovers_to_own_address() {
+        use super::super::settlement_auth::recover_address;
+        let signer = HdWalletSigner::from_mnemonic_index(GANACHE_MNEMONIC, 0).unwrap();
+        let sig = signer.sign_settlement(0, U256::from(500), U256::from(7));
+        let recovered = recover_address(0, U256::from(500), U256::from(7), &sig).unwrap();
+        assert_eq!(recovered, signer.address());
+    }
+
+    #[test]
+    fn rejects_invalid_checksum() {
+        // Swap the last word so the BIP39 checksum no longer matches.
+        let bad = "abstract vacuum mammal awkward pudding scene penalty purchase dinner depart evoke abandon";
+        assert!(HdWalletSigner::from_mnemonic_index(bad, 0).is_err());
+    }
+}
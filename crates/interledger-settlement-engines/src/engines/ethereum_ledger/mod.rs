@@ -0,0 +1,8 @@
+pub mod erc20;
+pub mod eth_engine;
+pub mod hd_wallet;
+pub mod settlement_auth;
+pub mod types;
+
+#[cfg(test)]
+pub mod test_helpers;
@@ -15,11 +15,14 @@ use std::time::Duration;
 
 use ethereum_tx_sign::web3::{
     futures::future::{err, ok, Future},
-    types::{Address, U256},
+    types::Address,
 };
 
 use super::eth_engine::EthereumLedgerSettlementEngine;
-use super::types::{Addresses, EthereumAccount, EthereumLedgerTxSigner, EthereumStore};
+use super::types::{
+    Addresses, EthereumAccount, EthereumLedgerTxSigner, EthereumStore, StorageIntermediate,
+    StorageIo,
+};
 
 #[derive(Debug, Clone)]
 pub struct TestAccount {
@@ -54,86 +57,36 @@ impl EthereumAccount for TestAccount {
 pub struct TestStore {
     pub accounts: Arc<Vec<TestAccount>>,
     pub should_fail: bool,
-    pub addresses: Arc<RwLock<HashMap<u64, Addresses>>>,
-    pub address_to_id: Arc<RwLock<HashMap<Addresses, u64>>>,
     #[allow(clippy::all)]
     pub cache: Arc<RwLock<HashMap<String, (StatusCode, String, [u8; 32])>>>,
-    pub last_observed_block: Arc<RwLock<U256>>,
-    pub last_observed_balance: Arc<RwLock<U256>>,
+    // Raw byte key-value map backing `StorageIo`. Account addresses and the
+    // ring buffer of recently observed blocks are serialized into here by the
+    // default `EthereumStore` methods, so the test double only has to provide
+    // the two primitive accessors.
+    pub kv: Arc<RwLock<HashMap<Vec<u8>, Vec<u8>>>>,
     pub cache_hits: Arc<RwLock<u64>>,
 }
 
-impl EthereumStore for TestStore {
-    type Account = TestAccount;
-
-    fn save_account_addresses(
-        &self,
-        account_ids: Vec<u64>,
-        data: Vec<Addresses>,
-    ) -> Box<Future<Item = (), Error = ()> + Send> {
-        let mut guard = self.addresses.write();
-        let mut guard2 = self.address_to_id.write();
-        for (acc, d) in account_ids.into_iter().zip(data.into_iter()) {
-            (*guard).insert(acc, d);
-            (*guard2).insert(d, acc);
-        }
-        Box::new(ok(()))
-    }
-
-    fn load_account_addresses(
+// `TestStore` only implements the two `StorageIo` primitives; every
+// `EthereumStore` method is provided by the blanket impl over `StorageIo` in
+// `super::types`, so serialization logic lives in exactly one place.
+impl StorageIo for TestStore {
+    fn read_storage(
         &self,
-        account_ids: Vec<u64>,
-    ) -> Box<dyn Future<Item = Vec<Addresses>, Error = ()> + Send> {
-        let mut v = Vec::with_capacity(account_ids.len());
-        let addresses = self.addresses.read();
-        for acc in &account_ids {
-            if let Some(d) = addresses.get(&acc) {
-                v.push(Addresses {
-                    own_address: d.own_address,
-                    token_address: d.token_address,
-                });
-            } else {
-                // if the account is not found, error out
-                return Box::new(err(()));
-            }
-        }
-        Box::new(ok(v))
+        key: Vec<u8>,
+    ) -> Box<dyn Future<Item = Option<StorageIntermediate>, Error = ()> + Send> {
+        let kv = self.kv.read();
+        Box::new(ok(kv.get(&key).cloned().map(StorageIntermediate::from)))
     }
 
-    fn save_recently_observed_data(
+    fn write_storage(
         &self,
-        block: U256,
-        balance: U256,
+        key: Vec<u8>,
+        bytes: Vec<u8>,
     ) -> Box<dyn Future<Item = (), Error = ()> + Send> {
-        let mut guard = self.last_observed_block.write();
-        *guard = block;
-        let mut guard = self.last_observed_balance.write();
-        *guard = balance;
+        self.kv.write().insert(key, bytes);
         Box::new(ok(()))
     }
-
-    fn load_recently_observed_data(
-        &self,
-    ) -> Box<dyn Future<Item = (U256, U256), Error = ()> + Send> {
-        Box::new(ok((
-            *self.last_observed_block.read(),
-            *self.last_observed_balance.read(),
-        )))
-    }
-
-    fn load_account_id_from_address(
-        &self,
-        eth_address: Addresses,
-    ) -> Box<dyn Future<Item = u64, Error = ()> + Send> {
-        let addresses = self.address_to_id.read();
-        let d = if let Some(d) = addresses.get(&eth_address) {
-            *d
-        } else {
-            return Box::new(err(()));
-        };
-
-        Box::new(ok(d))
-    }
 }
 
 impl AccountStore for TestStore {
@@ -201,34 +154,40 @@ impl IdempotentStore for TestStore {
 
 impl TestStore {
     pub fn new(accs: Vec<TestAccount>, should_fail: bool, initialize: bool) -> Self {
-        let mut addresses = HashMap::new();
-        let mut address_to_id = HashMap::new();
-        if initialize {
-            for account in &accs {
-                let token_address = if !account.no_details {
-                    Some(account.token_address)
-                } else {
-                    None
-                };
-                let addrs = Addresses {
-                    own_address: account.address,
-                    token_address,
-                };
-                addresses.insert(account.id, addrs);
-                address_to_id.insert(addrs, account.id);
-            }
-        }
-
-        TestStore {
+        let store = TestStore {
             accounts: Arc::new(accs),
             should_fail,
-            addresses: Arc::new(RwLock::new(addresses)),
-            address_to_id: Arc::new(RwLock::new(address_to_id)),
             cache: Arc::new(RwLock::new(HashMap::new())),
             cache_hits: Arc::new(RwLock::new(0)),
-            last_observed_balance: Arc::new(RwLock::new(U256::from(0))),
-            last_observed_block: Arc::new(RwLock::new(U256::from(0))),
+            kv: Arc::new(RwLock::new(HashMap::new())),
+        };
+
+        if initialize {
+            let (ids, data): (Vec<u64>, Vec<Addresses>) = store
+                .accounts
+                .iter()
+                .map(|account| {
+                    let token_address = if !account.no_details {
+                        Some(account.token_address)
+                    } else {
+                        None
+                    };
+                    (
+                        account.id,
+                        Addresses {
+                            own_address: account.address,
+                            token_address,
+                        },
+                    )
+                })
+                .unzip();
+            store
+                .save_account_addresses(ids, data)
+                .wait()
+                .expect("failed to seed test store addresses");
         }
+
+        store
     }
 }
 
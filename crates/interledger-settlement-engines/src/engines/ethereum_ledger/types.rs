@@ -0,0 +1,285 @@
+use ethereum_tx_sign::web3::{
+    futures::future::{join_all, Future},
+    types::{Address, H256, U256},
+};
+use ethereum_tx_sign::RawTransaction;
+
+use interledger_service::{Account, AccountStore};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::settlement_auth::Signature;
+
+/// Namespaces the different record kinds stored in a single flat KV backend so
+/// that e.g. an id→address mapping never collides with an address→id one.
+#[derive(Debug, Clone, Copy)]
+pub enum KeyPrefix {
+    IdToAddress,
+    AddressToId,
+    RecentlyObserved,
+}
+
+impl KeyPrefix {
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            KeyPrefix::IdToAddress => b"eth:id_to_address:",
+            KeyPrefix::AddressToId => b"eth:address_to_id:",
+            KeyPrefix::RecentlyObserved => b"eth:recently_observed",
+        }
+    }
+
+    /// Build a storage key by appending the bincode-encoded discriminator to
+    /// the prefix.
+    fn key<K: Serialize>(self, discriminator: &K) -> Vec<u8> {
+        let mut key = self.as_bytes().to_vec();
+        key.extend_from_slice(&bincode::serialize(discriminator).expect("serializable key"));
+        key
+    }
+}
+
+/// A single observed block in the reorg-detection window: its number, hash and
+/// the settlement-relevant balance seen at that height.
+pub type ObservedBlock = (U256, H256, U256);
+
+/// How many observed blocks to retain. Sized comfortably above any realistic
+/// `confs`, which bounds how far back a reorg can roll the cursor.
+pub const OBSERVED_WINDOW: usize = 64;
+
+/// The on-chain identity of an account: the address it settles from and,
+/// optionally, the ERC20 token contract it settles in (native ETH when `None`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Addresses {
+    pub own_address: Address,
+    pub token_address: Option<Address>,
+}
+
+/// An account that can participate in Ethereum settlement.
+pub trait EthereumAccount: Account {
+    fn own_address(&self) -> Address;
+
+    fn token_address(&self) -> Option<Address> {
+        None
+    }
+}
+
+/// Something that can sign settlement transactions and authorizations on behalf
+/// of an Ethereum account.
+pub trait EthereumLedgerTxSigner {
+    /// RLP-encode and sign `tx`, returning the raw bytes ready to broadcast.
+    fn sign(&self, tx: RawTransaction, chain_id: &u8) -> Vec<u8>;
+
+    /// The Ethereum address this signer controls.
+    fn address(&self) -> Address;
+
+    /// Produce a signed authorization over the canonical
+    /// `(account_id, amount, nonce)` tuple so the connector can verify a
+    /// settlement notification really came from this engine.
+    fn sign_settlement(&self, account_id: u64, amount: U256, nonce: U256) -> Signature;
+}
+
+/// A handle to a value read out of a [`StorageIo`] backend. It owns the bytes
+/// the backend returned; they are only copied out (or deserialized) when
+/// `to_vec`/`to_value` is called, so testing for presence needs neither step.
+pub struct StorageIntermediate(Vec<u8>);
+
+impl From<Vec<u8>> for StorageIntermediate {
+    fn from(bytes: Vec<u8>) -> Self {
+        StorageIntermediate(bytes)
+    }
+}
+
+impl StorageIntermediate {
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+
+    pub fn to_value<T: DeserializeOwned>(&self) -> Result<T, ()> {
+        bincode::deserialize(&self.0).map_err(|_| ())
+    }
+}
+
+/// The lowest-level storage interface a backend must provide: read and write
+/// raw bytes by key. Every higher-level [`EthereumStore`] method is a default
+/// method built on top of these two, so a new backend only implements these.
+pub trait StorageIo {
+    fn read_storage(
+        &self,
+        key: Vec<u8>,
+    ) -> Box<dyn Future<Item = Option<StorageIntermediate>, Error = ()> + Send>;
+
+    fn write_storage(
+        &self,
+        key: Vec<u8>,
+        bytes: Vec<u8>,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send>;
+}
+
+/// Account-address bookkeeping for the settlement engine, expressed entirely in
+/// terms of [`StorageIo`] + [`KeyPrefix`]. Implemented as a blanket impl so any
+/// KV backend gets it for free.
+pub trait EthereumStore: StorageIo {
+    type Account: EthereumAccount;
+
+    fn save_account_addresses(
+        &self,
+        account_ids: Vec<<Self::Account as Account>::AccountId>,
+        data: Vec<Addresses>,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send>
+    where
+        <Self::Account as Account>::AccountId: Serialize,
+    {
+        let mut writes: Vec<Box<dyn Future<Item = (), Error = ()> + Send>> = Vec::new();
+        for (id, addresses) in account_ids.into_iter().zip(data.into_iter()) {
+            let encoded = bincode::serialize(&addresses).expect("serializable addresses");
+            writes.push(self.write_storage(
+                KeyPrefix::IdToAddress.key(&id),
+                encoded.clone(),
+            ));
+            writes.push(self.write_storage(
+                KeyPrefix::AddressToId.key(&addresses),
+                bincode::serialize(&id).expect("serializable id"),
+            ));
+        }
+        Box::new(join_all(writes).map(|_| ()))
+    }
+
+    fn load_account_addresses(
+        &self,
+        account_ids: Vec<<Self::Account as Account>::AccountId>,
+    ) -> Box<dyn Future<Item = Vec<Addresses>, Error = ()> + Send>
+    where
+        <Self::Account as Account>::AccountId: Serialize,
+    {
+        let reads = account_ids
+            .into_iter()
+            .map(|id| {
+                self.read_storage(KeyPrefix::IdToAddress.key(&id))
+                    .and_then(|maybe| maybe.ok_or(()))
+                    .and_then(|intermediate| intermediate.to_value::<Addresses>())
+            })
+            .collect::<Vec<_>>();
+        Box::new(join_all(reads))
+    }
+
+    /// Resolve an account from its on-chain identity. Because [`Addresses`]
+    /// carries the token contract, the lookup keys on the full
+    /// `(own_address, token_address)` pair: the same address settling in ETH and
+    /// in an ERC20 resolves to different accounts.
+    fn load_account_id_from_address(
+        &self,
+        eth_address: Addresses,
+    ) -> Box<dyn Future<Item = <Self::Account as Account>::AccountId, Error = ()> + Send>
+    where
+        <Self::Account as Account>::AccountId: DeserializeOwned,
+    {
+        Box::new(
+            self.read_storage(KeyPrefix::AddressToId.key(&eth_address))
+                .and_then(|maybe| maybe.ok_or(()))
+                .and_then(|intermediate| {
+                    intermediate.to_value::<<Self::Account as Account>::AccountId>()
+                }),
+        )
+    }
+
+    /// Resolve the payer behind an ERC20 `Transfer` into one of our accounts,
+    /// keyed by the token contract the transfer occurred on and the sender
+    /// address. Thin wrapper over [`load_account_id_from_address`] that fixes
+    /// the token contract, so stablecoin transfers credit the right peer.
+    fn load_account_id_from_token_transfer(
+        &self,
+        token_address: Address,
+        from_address: Address,
+    ) -> Box<dyn Future<Item = <Self::Account as Account>::AccountId, Error = ()> + Send>
+    where
+        <Self::Account as Account>::AccountId: DeserializeOwned,
+    {
+        self.load_account_id_from_address(Addresses {
+            own_address: from_address,
+            token_address: Some(token_address),
+        })
+    }
+
+    /// Look up the `own_address` stored for an account, so a recovered
+    /// signature address can be checked against the account that claims to have
+    /// signed it.
+    fn load_account_address(
+        &self,
+        account_id: <Self::Account as Account>::AccountId,
+    ) -> Box<dyn Future<Item = Address, Error = ()> + Send>
+    where
+        <Self::Account as Account>::AccountId: Serialize,
+    {
+        Box::new(
+            self.read_storage(KeyPrefix::IdToAddress.key(&account_id))
+                .and_then(|maybe| maybe.ok_or(()))
+                .and_then(|intermediate| intermediate.to_value::<Addresses>())
+                .map(|addresses| addresses.own_address),
+        )
+    }
+
+    /// Append a freshly observed block to the reorg-detection window. If we are
+    /// re-observing a height we already recorded (e.g. after rolling the cursor
+    /// back across a reorg), that entry and everything newer is dropped first so
+    /// the window only ever holds the canonical chain. The window is then capped
+    /// at [`OBSERVED_WINDOW`] entries.
+    fn save_recently_observed_data(
+        &self,
+        block_number: U256,
+        block_hash: H256,
+        balance: U256,
+    ) -> Box<dyn Future<Item = (), Error = ()> + Send>
+    where
+        Self: Clone + Send + 'static,
+    {
+        let key = KeyPrefix::RecentlyObserved.as_bytes().to_vec();
+        let store = self.clone();
+        Box::new(self.load_recently_observed_data().and_then(move |mut window| {
+            window.drain_reorg(block_number);
+            window.push((block_number, block_hash, balance));
+            while window.len() > OBSERVED_WINDOW {
+                window.remove(0);
+            }
+            let encoded = bincode::serialize(&window).expect("serializable window");
+            store.write_storage(key, encoded)
+        }))
+    }
+
+    /// Return the full reorg-detection window, oldest first, so the engine can
+    /// compare stored hashes against the node's current chain.
+    fn load_recently_observed_data(
+        &self,
+    ) -> Box<dyn Future<Item = Vec<ObservedBlock>, Error = ()> + Send> {
+        Box::new(
+            self.read_storage(KeyPrefix::RecentlyObserved.as_bytes().to_vec())
+                .map(|maybe| {
+                    maybe
+                        .and_then(|i| i.to_value::<Vec<ObservedBlock>>().ok())
+                        .unwrap_or_default()
+                }),
+        )
+    }
+}
+
+// Small helper so the reorg-truncation step in `save_recently_observed_data`
+// reads clearly.
+trait DrainReorg {
+    fn drain_reorg(&mut self, from_number: U256);
+}
+
+impl DrainReorg for Vec<ObservedBlock> {
+    fn drain_reorg(&mut self, from_number: U256) {
+        while self.last().map(|(n, _, _)| *n >= from_number).unwrap_or(false) {
+            self.pop();
+        }
+    }
+}
+
+// Blanket impl: any `StorageIo` that is also an `AccountStore` of Ethereum
+// accounts is automatically an `EthereumStore`, so existing callers compile
+// unchanged.
+impl<S> EthereumStore for S
+where
+    S: StorageIo + AccountStore,
+    <S as AccountStore>::Account: EthereumAccount,
+{
+    type Account = <S as AccountStore>::Account;
+}